@@ -0,0 +1,130 @@
+use std::process::Command;
+
+/// A backend capable of applying a keyboard layout on the running session.
+pub trait LayoutBackend {
+    /// Applies the given layout (e.g. "fr", "us").
+    /// Returns an error message if the layout could not be applied.
+    fn apply(&self, layout: &str) -> Result<(), String>;
+}
+
+/// Switches the layout via the X11 `setxkbmap` command line tool.
+pub struct SetXkbMap;
+
+impl LayoutBackend for SetXkbMap {
+    fn apply(&self, layout: &str) -> Result<(), String> {
+        let command = format!("/usr/bin/setxkbmap {}", layout);
+        let output = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&command)
+            .output()
+            .map_err(|err| err.to_string())?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        }
+    }
+}
+
+/// Switches the layout on a Sway session by sending an `input ... xkb_layout`
+/// command over the sway IPC socket.
+pub struct Sway;
+
+impl LayoutBackend for Sway {
+    fn apply(&self, layout: &str) -> Result<(), String> {
+        let mut connection = swayipc::Connection::new().map_err(|err| err.to_string())?;
+        let command = format!("input type:keyboard xkb_layout {}", layout);
+
+        for result in connection
+            .run_command(command)
+            .map_err(|err| err.to_string())?
+        {
+            result.map_err(|err| err.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Switches the layout via the `xkb-switch` command line tool.
+pub struct XkbSwitch;
+
+impl LayoutBackend for XkbSwitch {
+    fn apply(&self, layout: &str) -> Result<(), String> {
+        let output = Command::new("xkb-switch")
+            .arg("-s")
+            .arg(layout)
+            .output()
+            .map_err(|err| err.to_string())?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        }
+    }
+}
+
+/// Switches the layout through systemd-localed over D-Bus.
+pub struct LocaleBus;
+
+impl LayoutBackend for LocaleBus {
+    fn apply(&self, layout: &str) -> Result<(), String> {
+        let connection = dbus::blocking::Connection::new_system().map_err(|err| err.to_string())?;
+        let proxy = connection.with_proxy(
+            "org.freedesktop.locale1",
+            "/org/freedesktop/locale1",
+            std::time::Duration::from_millis(5000),
+        );
+
+        proxy
+            .method_call::<(), _, _, _>(
+                "org.freedesktop.locale1",
+                "SetX11Keyboard",
+                (layout, "", "", "", false, false),
+            )
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Picks a backend based on the running graphical session.
+/// Uses `Sway` only when `$SWAYSOCK` points at an actual sway IPC socket;
+/// other Wayland compositors (GNOME, KDE, ...) fall back to `LocaleBus`,
+/// since they don't speak the sway IPC protocol. That fallback only
+/// updates the X11/Xwayland default layout via systemd-localed, it does
+/// not change the compositor's own live layout, so it's a best effort on
+/// those sessions and a warning is logged when it's used. Falls back to
+/// X11's `setxkbmap` when neither is set.
+pub fn detect_backend() -> Box<dyn LayoutBackend> {
+    let sway_socket = std::env::var("SWAYSOCK")
+        .map(|path| {
+            use std::os::unix::fs::FileTypeExt;
+            std::fs::metadata(path)
+                .map(|meta| meta.file_type().is_socket())
+                .unwrap_or(false)
+        })
+        .unwrap_or(false);
+
+    if sway_socket {
+        Box::new(Sway)
+    } else if std::env::var("WAYLAND_DISPLAY").is_ok() {
+        simple_log::warn!(
+            "Falling back to the systemd-localed backend on a non-Sway Wayland session; \
+             this only updates the X11/Xwayland default layout, not the compositor's live one"
+        );
+        Box::new(LocaleBus)
+    } else {
+        Box::new(SetXkbMap)
+    }
+}
+
+/// Builds the backend explicitly selected via `--backend`.
+pub fn from_kind(kind: crate::cli::BackendKind) -> Box<dyn LayoutBackend> {
+    match kind {
+        crate::cli::BackendKind::Setxkbmap => Box::new(SetXkbMap),
+        crate::cli::BackendKind::Sway => Box::new(Sway),
+        crate::cli::BackendKind::XkbSwitch => Box::new(XkbSwitch),
+        crate::cli::BackendKind::LocaleBus => Box::new(LocaleBus),
+    }
+}
@@ -0,0 +1,49 @@
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// Keyboard layout switcher: flips the X11/Wayland keyboard layout based on
+/// the presence of a configured USB device.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "keyb_layout_switcher", version, about)]
+pub struct Cli {
+    /// Override the configured USB device id, e.g. "445a:1121".
+    #[arg(long)]
+    pub usb_id: Option<String>,
+
+    /// Layout to apply when the device is present, e.g. "us".
+    #[arg(long)]
+    pub layout_present: Option<String>,
+
+    /// Layout to apply when the device is absent, e.g. "fr".
+    #[arg(long)]
+    pub layout_absent: Option<String>,
+
+    /// Poll interval in milliseconds, used when hotplug is unavailable.
+    #[arg(long)]
+    pub poll_interval: Option<u64>,
+
+    /// Layout backend to use instead of autodetecting one.
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// Log level passed to the logger.
+    #[arg(long, default_value = "debug")]
+    pub log_level: String,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// List every connected USB device with its vendor:product id.
+    ListDevices,
+}
+
+/// The layout backends selectable from the command line.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum BackendKind {
+    Setxkbmap,
+    Sway,
+    XkbSwitch,
+    LocaleBus,
+}
@@ -0,0 +1,143 @@
+use inotify::{Inotify, WatchMask};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Configuration for a single USB device to watch.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DeviceConfig {
+    pub usb_id: String,
+    pub layout_when_present: String,
+    pub layout_when_absent: String,
+    /// Command run after the layout is applied on device arrival, e.g. to
+    /// notify a status bar or toggle an LED.
+    #[serde(default)]
+    pub hook_when_present: Option<String>,
+    /// Command run after the layout is applied on device removal.
+    #[serde(default)]
+    pub hook_when_absent: Option<String>,
+}
+
+/// Top level configuration loaded from `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    #[serde(rename = "device", default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+fn default_poll_interval_ms() -> u64 {
+    500
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            poll_interval_ms: default_poll_interval_ms(),
+            devices: Vec::new(),
+        }
+    }
+}
+
+/// Returns the path to the config file.
+/// Looks up `$XDG_CONFIG_HOME/keyb_layout_switcher/config.toml`, falling
+/// back to `$HOME/.config/keyb_layout_switcher/config.toml` when
+/// `XDG_CONFIG_HOME` is unset.
+pub fn config_path() -> PathBuf {
+    let base = std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+            PathBuf::from(home).join(".config")
+        });
+
+    base.join("keyb_layout_switcher").join("config.toml")
+}
+
+/// Loads the configuration from disk.
+/// Returns an error if the file could not be read or parsed.
+/// Arguments:
+/// - None.
+/// Returns:
+/// - an error if the config file could not be read or parsed.
+/// - Ok(Config) if everything went fine.
+pub fn load_config() -> std::result::Result<Config, String> {
+    let path = config_path();
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+
+    toml::from_str(&contents).map_err(|err| format!("Failed to parse {}: {}", path.display(), err))
+}
+
+/// Watches the config file's directory for changes and hot-reloads it.
+/// Spawns a background thread that watches via inotify and, on a
+/// modify/close-write event for the config file, re-parses it, passes it
+/// through `post_load` and atomically swaps the shared `config`. On a parse
+/// error, the previous known-good config is kept and the error is logged.
+/// Arguments:
+/// - `config` - The shared config to keep up to date.
+/// - `post_load` - Run on every freshly parsed config before it replaces
+///   the shared one, e.g. to re-apply CLI overrides that a bare file
+///   reload would otherwise wipe out.
+pub fn watch_config(
+    config: Arc<RwLock<Config>>,
+    post_load: impl Fn(Config) -> Config + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        let path = config_path();
+        let dir = match path.parent() {
+            Some(dir) => dir.to_path_buf(),
+            None => return,
+        };
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_owned(),
+            None => return,
+        };
+
+        let mut inotify = match Inotify::init() {
+            Ok(inotify) => inotify,
+            Err(err) => {
+                simple_log::error!("Failed to initialize inotify: {}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = inotify
+            .watches()
+            .add(&dir, WatchMask::MODIFY | WatchMask::CLOSE_WRITE)
+        {
+            simple_log::error!("Failed to watch {}: {}", dir.display(), err);
+            return;
+        }
+
+        let mut buffer = [0; 1024];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    simple_log::error!("Failed to read inotify events: {}", err);
+                    continue;
+                }
+            };
+
+            let changed = events
+                .filter_map(|event| event.name.map(|name| name == file_name))
+                .any(|matches| matches);
+
+            if !changed {
+                continue;
+            }
+
+            match load_config() {
+                Ok(new_config) => {
+                    simple_log::info!("Reloaded config from {}", path.display());
+                    *config.write().unwrap() = post_load(new_config);
+                }
+                Err(str) => {
+                    simple_log::error!("Keeping previous config, failed to reload: {}", str);
+                }
+            }
+        }
+    });
+}
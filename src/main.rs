@@ -1,152 +1,439 @@
+mod backend;
+mod cli;
+mod config;
+
+use backend::LayoutBackend;
+use clap::Parser;
+use config::{Config, DeviceConfig};
 use rusb::UsbContext;
 use simple_log::LogConfigBuilder;
+use std::collections::HashMap;
 use std::process::Command;
-
-#[derive(Debug, PartialEq, Eq)]
-enum Signal {
-    ChangeAzerty,
-    ChangeQwerty,
-    NothingChanged,
-}
+use std::sync::{mpsc, Arc, RwLock};
 
 fn main() {
-    let mut is_connected = false;
-    let mut last_signal = Signal::NothingChanged;
-    let config = LogConfigBuilder::builder()
+    let cli = cli::Cli::parse();
+
+    let log_config = LogConfigBuilder::builder()
         .size(100)
         .roll_count(10)
-        .level("debug")
+        .level(cli.log_level.as_str())
         .output_console()
         .build();
 
-    if let Err(..) = simple_log::new(config) {
+    if let Err(..) = simple_log::new(log_config) {
         println!("Failed to initialize logger");
     }
 
-    loop {
-        let res = handle_usb_switch_logic(&mut is_connected);
-        let mut signal = Signal::NothingChanged;
+    if matches!(cli.command, Some(cli::Command::ListDevices)) {
+        list_devices();
+        return;
+    }
+
+    let config = config::load_config().unwrap_or_else(|str| {
+        simple_log::error!("{}", str);
+        Config::default()
+    });
+    let config = apply_cli_overrides(config, &cli);
+    if config.devices.is_empty() {
+        simple_log::error!(
+            "No devices configured; the daemon will idle until devices are added to the config \
+             or passed via --usb-id/--layout-present/--layout-absent"
+        );
+    }
+    let config = Arc::new(RwLock::new(config));
+    let cli_for_reload = cli.clone();
+    config::watch_config(Arc::clone(&config), move |loaded| {
+        apply_cli_overrides(loaded, &cli_for_reload)
+    });
+
+    let backend = cli
+        .backend
+        .map(backend::from_kind)
+        .unwrap_or_else(backend::detect_backend);
+
+    if rusb::has_hotplug() {
+        run_hotplug_loop(backend.as_ref(), &config);
+    } else {
+        simple_log::info!("libusb hotplug support unavailable, falling back to polling");
+        run_poll_loop(backend.as_ref(), &config);
+    }
+}
+
+/// Applies `--usb-id`/`--layout-present`/`--layout-absent`/`--poll-interval`
+/// overrides from the CLI on top of the loaded config. Each device flag is
+/// honored independently — even a single one, e.g. just `--usb-id` — and is
+/// applied to the first configured device only, creating a blank one if
+/// none exists yet. Any other `[[device]]` entries from the config file are
+/// left untouched, so the tool can also run without a config file at all.
+fn apply_cli_overrides(mut config: Config, cli: &cli::Cli) -> Config {
+    if let Some(poll_interval) = cli.poll_interval {
+        config.poll_interval_ms = poll_interval;
+    }
+
+    if cli.usb_id.is_some() || cli.layout_present.is_some() || cli.layout_absent.is_some() {
+        if config.devices.is_empty() {
+            config.devices.push(DeviceConfig {
+                usb_id: String::new(),
+                layout_when_present: String::new(),
+                layout_when_absent: String::new(),
+                hook_when_present: None,
+                hook_when_absent: None,
+            });
+        }
+
+        let device = &mut config.devices[0];
+
+        if let Some(usb_id) = &cli.usb_id {
+            device.usb_id = usb_id.clone();
+        }
+        if let Some(layout_present) = &cli.layout_present {
+            device.layout_when_present = layout_present.clone();
+        }
+        if let Some(layout_absent) = &cli.layout_absent {
+            device.layout_when_absent = layout_absent.clone();
+        }
+    }
+
+    config
+}
 
-        match res {
+/// Lists every connected USB device with its `vendor_id:product_id` and
+/// manufacturer/product strings, to help users find the id to configure.
+fn list_devices() {
+    let devices = match get_usb_devices() {
+        Ok(devices) => devices,
+        Err(str) => {
+            simple_log::error!("{}", str);
+            return;
+        }
+    };
+
+    for device in devices.iter() {
+        let desc = match device.device_descriptor() {
+            Ok(desc) => desc,
             Err(str) => {
                 simple_log::error!("{}", str);
                 continue;
             }
-            Ok(sig) => {
-                signal = sig;
-            }
+        };
+
+        let id = format!("{:04x}:{:04x}", desc.vendor_id(), desc.product_id());
+        let (manufacturer, product) = describe_device(&device, &desc);
+
+        println!("{}  {} {}", id, manufacturer, product);
+    }
+}
+
+/// Reads the manufacturer and product strings of a USB device.
+/// Returns `"<unknown>"` for either field when the device cannot be opened
+/// or does not expose the corresponding string descriptor.
+fn describe_device(
+    device: &rusb::Device<rusb::Context>,
+    desc: &rusb::DeviceDescriptor,
+) -> (String, String) {
+    let unknown = || "<unknown>".to_string();
+
+    let handle = match device.open() {
+        Ok(handle) => handle,
+        Err(_) => return (unknown(), unknown()),
+    };
+
+    let timeout = std::time::Duration::from_millis(100);
+    let language = match handle.read_languages(timeout).unwrap_or_default().first() {
+        Some(language) => *language,
+        None => return (unknown(), unknown()),
+    };
+
+    let manufacturer = handle
+        .read_manufacturer_string(language, desc, timeout)
+        .unwrap_or_else(|_| unknown());
+    let product = handle
+        .read_product_string(language, desc, timeout)
+        .unwrap_or_else(|_| unknown());
+
+    (manufacturer, product)
+}
+
+/// A layout to apply, plus the optional hook command to run once it has
+/// been applied successfully.
+#[derive(Debug, Clone)]
+struct LayoutChange {
+    layout: String,
+    hook: Option<String>,
+}
+
+/// Forwards libusb hotplug events for a single configured device to the
+/// main loop, as the layout change that should be applied on arrival/removal.
+struct HotplugHandler {
+    tx: mpsc::Sender<LayoutChange>,
+    on_present: LayoutChange,
+    on_absent: LayoutChange,
+}
+
+impl rusb::Hotplug<rusb::Context> for HotplugHandler {
+    fn device_arrived(&mut self, _device: rusb::Device<rusb::Context>) {
+        if let Err(str) = self.tx.send(self.on_present.clone()) {
+            simple_log::error!("{}", str);
+        }
+    }
+
+    fn device_left(&mut self, _device: rusb::Device<rusb::Context>) {
+        if let Err(str) = self.tx.send(self.on_absent.clone()) {
+            simple_log::error!("{}", str);
         }
+    }
+}
 
-        if signal != last_signal {
-            let res_change = change_keyboard_layout(&signal);
+/// Parses a `"vvvv:pppp"` USB id string into a (vendor_id, product_id) pair.
+fn parse_usb_id(usb_id: &str) -> std::result::Result<(u16, u16), String> {
+    let mut parts = usb_id.split(':');
+    let vendor_id = parts.next().ok_or("Missing vendor id")?;
+    let product_id = parts.next().ok_or("Missing product id")?;
 
-            if let Err(str) = res_change {
+    let vendor_id =
+        u16::from_str_radix(vendor_id, 16).map_err(|err| format!("Invalid vendor id: {}", err))?;
+    let product_id = u16::from_str_radix(product_id, 16)
+        .map_err(|err| format!("Invalid product id: {}", err))?;
+
+    Ok((vendor_id, product_id))
+}
+
+/// Registers a libusb hotplug callback for every configured device.
+/// Skips (and logs) devices whose `usb_id` fails to parse. Returned
+/// registrations must be kept alive for as long as the callbacks should
+/// stay active — dropping one unregisters it.
+fn register_hotplug_callbacks(
+    context: &rusb::Context,
+    devices_config: &[DeviceConfig],
+    tx: &mpsc::Sender<LayoutChange>,
+) -> Vec<rusb::Registration<rusb::Context>> {
+    let mut registrations = Vec::new();
+
+    for device_config in devices_config {
+        let (vendor_id, product_id) = match parse_usb_id(&device_config.usb_id) {
+            Ok(ids) => ids,
+            Err(str) => {
                 simple_log::error!("{}", str);
                 continue;
             }
+        };
+
+        let handler = HotplugHandler {
+            tx: tx.clone(),
+            on_present: LayoutChange {
+                layout: device_config.layout_when_present.clone(),
+                hook: device_config.hook_when_present.clone(),
+            },
+            on_absent: LayoutChange {
+                layout: device_config.layout_when_absent.clone(),
+                hook: device_config.hook_when_absent.clone(),
+            },
+        };
 
-            last_signal = signal;
+        let registration = rusb::HotplugBuilder::new()
+            .vendor_id(vendor_id)
+            .product_id(product_id)
+            .enumerate(true)
+            .register(context, Box::new(handler));
+
+        match registration {
+            Ok(registration) => registrations.push(registration),
+            Err(str) => simple_log::error!("{}", str),
         }
-        std::thread::sleep(std::time::Duration::from_millis(500));
     }
+
+    registrations
 }
 
-/// Change the keyboard layout according to the signal.
-/// Returns an error if the keyboard layout could not be changed.
-/// Returns Ok(()) if the keyboard layout was changed.
-/// Arguments:
-/// - signal: the signal to change the keyboard layout.
-/// Returns:
-/// - an error if the keyboard layout could not be changed.
-/// - Ok(()) if the keyboard layout was changed.
-fn change_keyboard_layout(signal: &Signal) -> std::result::Result<(), &'static str> {
-    match signal {
-        Signal::ChangeAzerty => {
-            let command = "/usr/bin/setxkbmap fr";
-            let output = Command::new("/bin/sh").arg("-c").arg(command).output();
-
-            match output {
-                Err(str) => {
+/// Runs the event-driven main loop.
+/// Registers a libusb hotplug callback per configured device and blocks on
+/// `handle_events` on a background thread, switching the keyboard layout as
+/// soon as arrival/removal events come in instead of waiting on a poll tick.
+/// Periodically re-reads the shared config and re-registers the hotplug
+/// callbacks whenever the device list changed, so a config reload (see
+/// `config::watch_config`) takes effect here too, not just in the poll loop.
+fn run_hotplug_loop(backend: &dyn LayoutBackend, config: &Arc<RwLock<Config>>) {
+    let context = match rusb::Context::new() {
+        Ok(context) => context,
+        Err(str) => {
+            simple_log::error!("{}", str);
+            return;
+        }
+    };
+
+    let event_context = context.clone();
+    std::thread::spawn(move || loop {
+        if let Err(str) = event_context.handle_events(None) {
+            simple_log::error!("{}", str);
+        }
+    });
+
+    let (tx, rx) = mpsc::channel();
+    let mut current_devices: Option<Vec<DeviceConfig>> = None;
+    let mut registrations = Vec::new();
+
+    loop {
+        let devices_config = config.read().unwrap().devices.clone();
+
+        if current_devices.as_ref() != Some(&devices_config) {
+            registrations = register_hotplug_callbacks(&context, &devices_config, &tx);
+            simple_log::info!(
+                "Hotplug callbacks registered for {} of {} configured device(s)",
+                registrations.len(),
+                devices_config.len()
+            );
+            current_devices = Some(devices_config);
+        }
+
+        match rx.recv_timeout(std::time::Duration::from_secs(1)) {
+            Ok(change) => {
+                if let Err(str) = change_keyboard_layout(backend, &change) {
                     simple_log::error!("{}", str);
-                    return Err("Failed to change keyboard layout");
-                }
-                Ok(out) => {
-                    if out.status.success() {
-                        simple_log::info!("Successfully changed keyboard layout to azerty");
-                    } else {
-                        simple_log::error!("{}", String::from_utf8_lossy(&out.stderr));
-                        return Err("Failed to change keyboard layout to azerty");
-                    }
                 }
             }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
         }
+    }
+}
 
-        Signal::ChangeQwerty => {
-            let command = "/usr/bin/setxkbmap us";
-            let output = Command::new("/bin/sh").arg("-c").arg(command).output();
+/// Runs the legacy polling loop.
+/// Used as a fallback on platforms where libusb hotplug support is
+/// unavailable (`rusb::has_hotplug()` returns false).
+fn run_poll_loop(backend: &dyn LayoutBackend, config: &Arc<RwLock<Config>>) {
+    let mut connected_state = HashMap::new();
 
-            match output {
-                Err(str) => {
-                    simple_log::error!("{}", str);
-                    return Err("Failed to change keyboard layout");
-                }
-                Ok(out) => {
-                    if out.status.success() {
-                        simple_log::info!("Successfully changed keyboard layout to azerty");
-                    } else {
-                        simple_log::error!("{}", String::from_utf8_lossy(&out.stderr));
-                        return Err("Failed to change keyboard layout to azerty");
+    loop {
+        let snapshot = config.read().unwrap().clone();
+
+        match handle_usb_switch_logic(&snapshot.devices, &mut connected_state) {
+            Err(str) => {
+                simple_log::error!("{}", str);
+            }
+            Ok(changes) => {
+                for change in changes {
+                    if let Err(str) = change_keyboard_layout(backend, &change) {
+                        simple_log::error!("{}", str);
                     }
                 }
             }
         }
+        std::thread::sleep(std::time::Duration::from_millis(snapshot.poll_interval_ms));
+    }
+}
 
-        Signal::NothingChanged => {}
+/// Change the keyboard layout according to `change`, using the given
+/// backend, then run its hook command if one is configured.
+/// Returns an error if the keyboard layout could not be changed. A failing
+/// hook is logged but never returned as an error, so it cannot abort the
+/// main loop.
+/// Arguments:
+/// - backend: the backend used to apply the layout.
+/// - change: the layout to apply and the optional hook to run afterwards.
+/// Returns:
+/// - an error if the keyboard layout could not be changed.
+/// - Ok(()) if the keyboard layout was changed.
+fn change_keyboard_layout(
+    backend: &dyn LayoutBackend,
+    change: &LayoutChange,
+) -> std::result::Result<(), &'static str> {
+    match backend.apply(&change.layout) {
+        Ok(()) => {
+            simple_log::info!("Successfully changed keyboard layout to {}", change.layout);
+        }
+        Err(str) => {
+            simple_log::error!("{}", str);
+            return Err("Failed to change keyboard layout");
+        }
+    }
+
+    if let Some(hook) = &change.hook {
+        run_hook(hook);
     }
+
     Ok(())
 }
 
-/// Handles the logic of the USB switch.
+/// Runs a user-defined hook command and logs its exit status.
+/// Never returns an error: a broken hook must not abort the main loop.
+fn run_hook(hook: &str) {
+    match Command::new("/bin/sh").arg("-c").arg(hook).output() {
+        Ok(out) if out.status.success() => {
+            simple_log::info!("Hook `{}` exited successfully", hook);
+        }
+        Ok(out) => {
+            simple_log::error!(
+                "Hook `{}` exited with {}: {}",
+                hook,
+                out.status,
+                String::from_utf8_lossy(&out.stderr)
+            );
+        }
+        Err(str) => {
+            simple_log::error!("Failed to run hook `{}`: {}", hook, str);
+        }
+    }
+}
+
+/// Handles the logic of the USB switch for every configured device.
+/// Returns the list of layout changes that should be applied, in configured
+/// device order, for every device whose presence changed since the last
+/// call.
 /// Returns an error if something went wrong.
-/// Returns Ok(()) if everything went fine.
 /// Arguments
-/// - `is_connected` - A mutable boolean that is set to true if the USB switch is connected.
+/// - `devices_config` - The configured devices to watch.
+/// - `connected_state` - A map from `usb_id` to whether the device was
+///   connected as of the last call.
 /// Returns:
 /// - an error if something went wrong.
-/// - Ok(()) if everything went fine.
-fn handle_usb_switch_logic(is_connected: &mut bool) -> std::result::Result<Signal, &'static str> {
-    let devices = get_usb_devices().map_err(|_| "Failed to get USBdevices")?;
-    let target_id = "445a:1121";
-    let mut found = false;
-
-    devices.iter().for_each(|device| {
-        let desc = device.device_descriptor();
-        match desc {
+/// - Ok(Vec<LayoutChange>) with the changes to apply if everything went fine.
+fn handle_usb_switch_logic(
+    devices_config: &[DeviceConfig],
+    connected_state: &mut HashMap<String, bool>,
+) -> std::result::Result<Vec<LayoutChange>, &'static str> {
+    let devices = get_usb_devices().map_err(|_| "Failed to get USB devices")?;
+    let mut present_ids = std::collections::HashSet::new();
+
+    devices
+        .iter()
+        .for_each(|device| match device.device_descriptor() {
             Ok(desc) => {
-                let id = format!("{:04x}:{:04x}", desc.vendor_id(), desc.product_id());
-                if id == target_id {
-                    found = true;
-                }
+                present_ids.insert(format!(
+                    "{:04x}:{:04x}",
+                    desc.vendor_id(),
+                    desc.product_id()
+                ));
             }
             Err(str) => {
                 simple_log::error!("{}", str);
             }
-        }
-    });
+        });
 
-    if found && !(*is_connected) {
-        *is_connected = true;
-
-        return Ok(Signal::ChangeQwerty);
-    }
+    let mut changes = Vec::new();
 
-    if !found && *is_connected {
-        *is_connected = false;
+    for device_config in devices_config {
+        let is_present = present_ids.contains(&device_config.usb_id.to_lowercase());
+        let was_connected = *connected_state
+            .entry(device_config.usb_id.clone())
+            .or_insert(false);
 
-        return Ok(Signal::ChangeAzerty);
+        if is_present && !was_connected {
+            connected_state.insert(device_config.usb_id.clone(), true);
+            changes.push(LayoutChange {
+                layout: device_config.layout_when_present.clone(),
+                hook: device_config.hook_when_present.clone(),
+            });
+        } else if !is_present && was_connected {
+            connected_state.insert(device_config.usb_id.clone(), false);
+            changes.push(LayoutChange {
+                layout: device_config.layout_when_absent.clone(),
+                hook: device_config.hook_when_absent.clone(),
+            });
+        }
     }
 
-    Ok(Signal::NothingChanged)
+    Ok(changes)
 }
 
 /// Gets all USB devices.
@@ -168,20 +455,116 @@ fn get_usb_devices() -> std::result::Result<rusb::DeviceList<rusb::Context>, &'s
 mod tests {
     use super::*;
 
+    fn test_device_config() -> DeviceConfig {
+        DeviceConfig {
+            usb_id: "445a:1121".to_string(),
+            layout_when_present: "us".to_string(),
+            layout_when_absent: "fr".to_string(),
+            hook_when_present: None,
+            hook_when_absent: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_cli_overrides() {
+        let config = Config {
+            poll_interval_ms: 500,
+            devices: vec![test_device_config()],
+        };
+        let cli = cli::Cli::parse_from([
+            "keyb_layout_switcher",
+            "--usb-id",
+            "046d:c52b",
+            "--layout-present",
+            "fr",
+            "--layout-absent",
+            "us",
+            "--poll-interval",
+            "100",
+        ]);
+
+        let config = apply_cli_overrides(config, &cli);
+
+        assert_eq!(config.poll_interval_ms, 100);
+        assert_eq!(config.devices.len(), 1);
+        assert_eq!(config.devices[0].usb_id, "046d:c52b");
+    }
+
+    #[test]
+    fn test_apply_cli_overrides_partial() {
+        let config = Config {
+            poll_interval_ms: 500,
+            devices: vec![test_device_config()],
+        };
+        let cli = cli::Cli::parse_from(["keyb_layout_switcher", "--usb-id", "046d:c52b"]);
+
+        let config = apply_cli_overrides(config, &cli);
+
+        assert_eq!(config.devices.len(), 1);
+        assert_eq!(config.devices[0].usb_id, "046d:c52b");
+        assert_eq!(config.devices[0].layout_when_present, "us");
+        assert_eq!(config.devices[0].layout_when_absent, "fr");
+    }
+
+    #[test]
+    fn test_apply_cli_overrides_keeps_other_devices() {
+        let mut other_device = test_device_config();
+        other_device.usb_id = "046d:c52b".to_string();
+        let config = Config {
+            poll_interval_ms: 500,
+            devices: vec![test_device_config(), other_device],
+        };
+        let cli = cli::Cli::parse_from(["keyb_layout_switcher", "--usb-id", "045e:0040"]);
+
+        let config = apply_cli_overrides(config, &cli);
+
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.devices[0].usb_id, "045e:0040");
+        assert_eq!(config.devices[1].usb_id, "046d:c52b");
+    }
+
+    /// A `LayoutBackend` that just records the layouts it was asked to
+    /// apply, so tests don't depend on a real X11/Wayland session.
+    struct StubBackend {
+        applied: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl LayoutBackend for StubBackend {
+        fn apply(&self, layout: &str) -> Result<(), String> {
+            self.applied.borrow_mut().push(layout.to_string());
+            Ok(())
+        }
+    }
+
     #[test]
     fn test_change_keyboard_layout() {
-        let res = change_keyboard_layout(&Signal::ChangeAzerty);
+        let backend = StubBackend {
+            applied: std::cell::RefCell::new(Vec::new()),
+        };
+        let res = change_keyboard_layout(
+            &backend,
+            &LayoutChange {
+                layout: "fr".to_string(),
+                hook: None,
+            },
+        );
         assert!(res.is_ok());
-        let res = change_keyboard_layout(&Signal::ChangeQwerty);
-        assert!(res.is_ok());
-        let res = change_keyboard_layout(&Signal::NothingChanged);
+        let res = change_keyboard_layout(
+            &backend,
+            &LayoutChange {
+                layout: "us".to_string(),
+                hook: Some("true".to_string()),
+            },
+        );
         assert!(res.is_ok());
+        assert_eq!(*backend.applied.borrow(), vec!["fr", "us"]);
     }
 
     #[test]
     fn test_handle_usb_switch_logic() {
-        let mut is_connected = false;
-        let res = handle_usb_switch_logic(&mut is_connected);
+        let devices_config = vec![test_device_config()];
+        let mut connected_state = HashMap::new();
+        let res = handle_usb_switch_logic(&devices_config, &mut connected_state);
         assert!(res.is_ok());
     }
 
@@ -190,4 +573,11 @@ mod tests {
         let res = get_usb_devices();
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn test_parse_usb_id() {
+        let res = parse_usb_id("445a:1121");
+        assert_eq!(res, Ok((0x445a, 0x1121)));
+        assert!(parse_usb_id("not-an-id").is_err());
+    }
 }